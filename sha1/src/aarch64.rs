@@ -0,0 +1,18 @@
+//! Runtime detection of the AArch64 `SHA1` crypto extension.
+
+use libc::c_ulong;
+
+const AT_HWCAP: c_ulong = 16;
+const HWCAP_SHA1: c_ulong = 1 << 5;
+
+extern "C" {
+    fn getauxval(kind: c_ulong) -> c_ulong;
+}
+
+/// Returns `true` if the running CPU exposes the AArch64 `SHA1` crypto
+/// extension, as reported by the kernel's `AT_HWCAP` auxiliary vector entry.
+#[allow(unsafe_code)]
+pub fn sha1_supported() -> bool {
+    let hwcaps: c_ulong = unsafe { getauxval(AT_HWCAP) };
+    hwcaps & HWCAP_SHA1 != 0
+}
@@ -0,0 +1,433 @@
+//! Heuristic SHA-1 collision-attack detection, loosely inspired by Marc
+//! Stevens' `sha1collisiondetection` (the library behind Git's hardened
+//! SHA-1), but **not** a port of it and **not** equivalent to it.
+//!
+//! Known SHA-1 collisions (as used in the SHAttered and Shambles attacks)
+//! are built by appending a "near-collision block": a block crafted so
+//! that, combined with a single flipped message bit, it nudges the
+//! chaining value along a known differential path, or disturbance vector
+//! (DV). The real `sha1collisiondetection` recognises these by checking,
+//! for every DV and every round, a table of per-round boolean "sufficient
+//! conditions" on the working registers — that is what actually gives it
+//! its low false-positive *and* false-negative rate.
+//!
+//! This module does not do that. For every compressed block it retains
+//! the 80-word expanded message schedule and a snapshot of the working
+//! registers entering each round, then for each entry of
+//! [`DISTURBANCE_VECTORS`] it flips that DV's message-word bit and
+//! replays the round function from the DV's starting round to the end of
+//! the block. If the resulting chaining value lands suspiciously close
+//! (by Hamming distance) to the genuine one, the block is flagged. This
+//! end-state-distance heuristic is not one of the published sufficient
+//! conditions, [`DISTURBANCE_VECTORS`] is a small illustrative set rather
+//! than the reference implementation's full table, and neither is tuned
+//! against real SHAttered/Shambles blocks. Treat a `false` result as "no
+//! attack recognised by this heuristic", not "no attack occurred"; for an
+//! authoritative check, use `sha1collisiondetection` (or a binding to it)
+//! instead.
+
+use crate::consts::{H, K0, K1, K2, K3, STATE_LEN};
+use block_buffer::byteorder::{ByteOrder, BE};
+use block_buffer::BlockBuffer;
+use digest::generic_array::typenum::{U20, U64};
+use digest::generic_array::GenericArray;
+use digest::impl_write;
+use digest::{BlockInput, FixedOutput, Reset, Update};
+
+/// A single disturbance vector: the expanded-message-word index and bit
+/// position of a known SHA-1 differential path's first perturbation. The
+/// word index also doubles as the round the perturbation is introduced at,
+/// since expanded word `i` is consumed by round `i`; recomputation for this
+/// DV starts there rather than from round 0.
+#[derive(Clone, Copy)]
+pub struct DisturbanceVector {
+    /// Index into the 80-word expanded message schedule, and the round the
+    /// perturbation first affects.
+    pub word: usize,
+    /// Bit position (0 = LSB) perturbed within that word.
+    pub bit: u32,
+}
+
+/// A small illustrative set of disturbance vectors, loosely modelled on the
+/// kind of table `sha1collisiondetection` uses to recognise near-collision
+/// blocks. This is not that table: see the module docs for what's missing.
+pub static DISTURBANCE_VECTORS: &[DisturbanceVector] = &[
+    DisturbanceVector { word: 4, bit: 19 },
+    DisturbanceVector { word: 4, bit: 21 },
+    DisturbanceVector { word: 4, bit: 23 },
+    DisturbanceVector { word: 5, bit: 2 },
+    DisturbanceVector { word: 5, bit: 17 },
+    DisturbanceVector { word: 10, bit: 0 },
+    DisturbanceVector { word: 10, bit: 20 },
+    DisturbanceVector { word: 12, bit: 9 },
+];
+
+/// Maximum Hamming distance between a recomputed chaining value and the
+/// genuine one for the block to be flagged as an attack candidate. Real
+/// near-collision blocks are engineered to differ in only a handful of the
+/// 160 output bits; unrelated input overwhelmingly differs in close to
+/// half of them.
+const SUSPICIOUS_HAMMING_DISTANCE: u32 = 8;
+
+/// Round constants used to recompute the digest once a collision attack is
+/// detected in "safe hash" mode, chosen so the two inputs an attacker
+/// engineered to collide under the standard constants no longer do.
+const SAFE_K: [u32; 4] = [K0 ^ 1, K1 ^ 1, K2 ^ 1, K3 ^ 1];
+
+/// Expands a block into its 80-word message schedule.
+fn expand(block: &GenericArray<u8, U64>) -> [u32; 80] {
+    let mut w = [0u32; 80];
+    for (chunk, out) in block.chunks_exact(4).zip(w.iter_mut()) {
+        *out = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    w
+}
+
+/// Runs rounds `from..80` of the compression function over an
+/// already-expanded message schedule, starting from working registers
+/// `regs` (as entering round `from`) and using round constants `k`.
+/// Returns the working registers as they stand after round 79, before the
+/// chaining-value feed-forward add.
+fn run_rounds(
+    regs: [u32; STATE_LEN],
+    words: &[u32; 80],
+    from: usize,
+    k: [u32; 4],
+) -> [u32; STATE_LEN] {
+    let [mut a, mut b, mut c, mut d, mut e] = regs;
+    for (i, &w) in words.iter().enumerate().skip(from) {
+        let (f, k_i) = match i {
+            0..=19 => ((b & c) | ((!b) & d), k[0]),
+            20..=39 => (b ^ c ^ d, k[1]),
+            40..=59 => ((b & c) | (b & d) | (c & d), k[2]),
+            _ => (b ^ c ^ d, k[3]),
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k_i)
+            .wrapping_add(w);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+    [a, b, c, d, e]
+}
+
+/// Runs the single round `i` of the compression function, advancing the
+/// working registers from "entering round `i`" to "entering round `i + 1`".
+fn run_round(regs: [u32; STATE_LEN], w_i: u32, i: usize, k: [u32; 4]) -> [u32; STATE_LEN] {
+    let [a, b, c, d, e] = regs;
+    let (f, k_i) = match i {
+        0..=19 => ((b & c) | ((!b) & d), k[0]),
+        20..=39 => (b ^ c ^ d, k[1]),
+        40..=59 => ((b & c) | (b & d) | (c & d), k[2]),
+        _ => (b ^ c ^ d, k[3]),
+    };
+    let temp = a
+        .rotate_left(5)
+        .wrapping_add(f)
+        .wrapping_add(e)
+        .wrapping_add(k_i)
+        .wrapping_add(w_i);
+    [temp, a, b.rotate_left(30), c, d]
+}
+
+/// Runs the full 80-round compression function over an already-expanded
+/// message schedule, returning the working registers entering every round
+/// (so snapshot `i` is valid as a `from = i` starting point for
+/// [`run_rounds`]).
+fn round_snapshots(
+    state: [u32; STATE_LEN],
+    words: &[u32; 80],
+    k: [u32; 4],
+) -> [[u32; STATE_LEN]; 80] {
+    let mut snapshots = [[0u32; STATE_LEN]; 80];
+    let mut regs = state;
+    for (i, snapshot) in snapshots.iter_mut().enumerate() {
+        *snapshot = regs;
+        regs = run_round(regs, words[i], i, k);
+    }
+    snapshots
+}
+
+/// Runs the 80-round compression function over an already-expanded message
+/// schedule, starting from `state` and using round constants `k`.
+fn compress_words(state: [u32; STATE_LEN], words: &[u32; 80], k: [u32; 4]) -> [u32; STATE_LEN] {
+    let final_regs = run_rounds(state, words, 0, k);
+    [
+        state[0].wrapping_add(final_regs[0]),
+        state[1].wrapping_add(final_regs[1]),
+        state[2].wrapping_add(final_regs[2]),
+        state[3].wrapping_add(final_regs[3]),
+        state[4].wrapping_add(final_regs[4]),
+    ]
+}
+
+fn hamming_distance(a: [u32; STATE_LEN], b: [u32; STATE_LEN]) -> u32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(x, y)| (x ^ y).count_ones())
+        .sum()
+}
+
+/// Checks a compressed block against every known disturbance vector,
+/// returning `true` if replaying it from the DV's starting round with that
+/// DV's bit flipped lands suspiciously close to the genuine chaining value.
+///
+/// `snapshots` are the working registers entering each round of the
+/// genuine computation, as returned by [`round_snapshots`]; round `i`'s
+/// registers are unaffected by a perturbation first introduced at round
+/// `i`, so replaying from there (rather than from round 0) is exact — but
+/// only once every expanded word that transitively depends on the flipped
+/// one has also been recomputed. `w[t] = ROTL1(w[t-3]^w[t-8]^w[t-14]^w[t-16])`
+/// for `t >= 16`, so flipping a raw message word (`dv.word < 16`, true of
+/// every entry in [`DISTURBANCE_VECTORS`] today) can change any expanded
+/// word from 16 onward; this re-runs that recurrence over words `16..80`
+/// of `perturbed` before
+/// replaying the rounds, so the schedule fed to [`run_rounds`] matches a
+/// real 64-byte block with that bit flipped.
+fn detect_block(
+    initial_state: [u32; STATE_LEN],
+    snapshots: &[[u32; STATE_LEN]; 80],
+    words: &[u32; 80],
+    genuine: [u32; STATE_LEN],
+) -> bool {
+    for dv in DISTURBANCE_VECTORS {
+        let mut perturbed = *words;
+        perturbed[dv.word] ^= 1 << dv.bit;
+        for t in (dv.word + 1).max(16)..80 {
+            perturbed[t] =
+                (perturbed[t - 3] ^ perturbed[t - 8] ^ perturbed[t - 14] ^ perturbed[t - 16])
+                    .rotate_left(1);
+        }
+        let final_regs = run_rounds(snapshots[dv.word], &perturbed, dv.word, [K0, K1, K2, K3]);
+        let candidate = [
+            initial_state[0].wrapping_add(final_regs[0]),
+            initial_state[1].wrapping_add(final_regs[1]),
+            initial_state[2].wrapping_add(final_regs[2]),
+            initial_state[3].wrapping_add(final_regs[3]),
+            initial_state[4].wrapping_add(final_regs[4]),
+        ];
+        if candidate != genuine
+            && hamming_distance(candidate, genuine) <= SUSPICIOUS_HAMMING_DISTANCE
+        {
+            return true;
+        }
+    }
+    false
+}
+
+/// A SHA-1 implementation that runs the heuristic collision-attack
+/// detector from this module's docs over every block.
+///
+/// Detection never changes the digest produced for honest input. Call
+/// [`Sha1Cd::collision_detected`] to check whether the heuristic flagged an
+/// attack block; [`fixed_result`](FixedOutput::fixed_result) consumes the
+/// hasher, so the flag can only be read before finalizing, and it then
+/// covers every block compressed so far but not the padding block(s) that
+/// finalization compresses. Alternatively, build with
+/// [`Sha1Cd::with_safe_hash`] to have the digest itself change when it
+/// does. See the module docs for what this detector does and does not
+/// catch — it is not equivalent to Git's hardened SHA-1.
+#[derive(Clone)]
+pub struct Sha1Cd {
+    h: [u32; STATE_LEN],
+    h_safe: [u32; STATE_LEN],
+    len: u64,
+    buffer: BlockBuffer<U64>,
+    collision_detected: bool,
+    safe_hash: bool,
+}
+
+impl Sha1Cd {
+    /// Enables "safe hash" mode: when a collision-attack block is detected,
+    /// [`fixed_result`](FixedOutput::fixed_result) returns a digest
+    /// recomputed with perturbed round constants instead of the genuine
+    /// one, so that the colliding inputs an attacker engineered no longer
+    /// produce the same output.
+    pub fn with_safe_hash(mut self) -> Self {
+        self.safe_hash = true;
+        self
+    }
+
+    /// Returns `true` if a block matching a known disturbance vector was
+    /// seen while hashing.
+    pub fn collision_detected(&self) -> bool {
+        self.collision_detected
+    }
+
+    fn compress_block(
+        h: &mut [u32; STATE_LEN],
+        h_safe: &mut [u32; STATE_LEN],
+        collision_detected: &mut bool,
+        block: &GenericArray<u8, U64>,
+    ) {
+        let words = expand(block);
+        let snapshots = round_snapshots(*h, &words, [K0, K1, K2, K3]);
+        let genuine = compress_words(*h, &words, [K0, K1, K2, K3]);
+        if detect_block(*h, &snapshots, &words, genuine) {
+            *collision_detected = true;
+        }
+        *h_safe = compress_words(*h_safe, &words, SAFE_K);
+        *h = genuine;
+    }
+}
+
+impl Default for Sha1Cd {
+    fn default() -> Self {
+        Sha1Cd {
+            h: H,
+            h_safe: H,
+            len: 0,
+            buffer: Default::default(),
+            collision_detected: false,
+            safe_hash: false,
+        }
+    }
+}
+
+impl BlockInput for Sha1Cd {
+    type BlockSize = U64;
+}
+
+impl Update for Sha1Cd {
+    fn update(&mut self, input: impl AsRef<[u8]>) {
+        let input = input.as_ref();
+        self.len += input.len() as u64;
+        let h = &mut self.h;
+        let h_safe = &mut self.h_safe;
+        let collision_detected = &mut self.collision_detected;
+        self.buffer.input(input, |d| {
+            Self::compress_block(h, h_safe, collision_detected, d)
+        });
+    }
+}
+
+impl FixedOutput for Sha1Cd {
+    type OutputSize = U20;
+
+    fn fixed_result(mut self) -> GenericArray<u8, Self::OutputSize> {
+        {
+            let h = &mut self.h;
+            let h_safe = &mut self.h_safe;
+            let collision_detected = &mut self.collision_detected;
+            let l = self.len << 3;
+            self.buffer.len64_padding::<BE, _>(l, |d| {
+                Self::compress_block(h, h_safe, collision_detected, d)
+            });
+        }
+        let h = if self.safe_hash && self.collision_detected {
+            self.h_safe
+        } else {
+            self.h
+        };
+        let mut out = GenericArray::default();
+        BE::write_u32_into(&h, &mut out);
+        out
+    }
+}
+
+impl Reset for Sha1Cd {
+    fn reset(&mut self) {
+        self.h = H;
+        self.h_safe = H;
+        self.len = 0;
+        self.buffer.reset();
+        self.collision_detected = false;
+    }
+}
+
+impl_opaque_debug!(Sha1Cd);
+impl_write!(Sha1Cd);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Sha1;
+    use digest::Digest;
+
+    #[test]
+    fn matches_scalar_digest_for_honest_input() {
+        let mut cd = Sha1Cd::default();
+        cd.update(b"the quick brown fox jumps over the lazy dog, more than once");
+
+        let mut scalar = Sha1::new();
+        scalar.update(b"the quick brown fox jumps over the lazy dog, more than once");
+
+        assert_eq!(cd.clone().fixed_result(), scalar.result());
+    }
+
+    #[test]
+    fn no_false_positive_on_honest_multi_block_input() {
+        let mut cd = Sha1Cd::default();
+        // Nine full blocks (600 bytes) worth of data, none of it crafted.
+        for i in 0..150u32 {
+            cd.update(i.to_be_bytes());
+        }
+        assert!(!cd.collision_detected());
+    }
+
+    #[test]
+    fn detect_block_flags_a_genuine_near_match() {
+        // This module ships no real SHA-1 near-collision block (see the
+        // module docs: finding one is a multi-year cryptanalysis result,
+        // not something this heuristic detector can manufacture), so this
+        // exercises the detection arithmetic directly rather than through
+        // `Sha1Cd`: run the exact replay `detect_block` does for a real
+        // block against the first disturbance vector, then assert it's
+        // flagged once the *comparison value* happens to land inside
+        // `SUSPICIOUS_HAMMING_DISTANCE` of what that replay produces. This
+        // is what proves the `true` branch (and the round/word recomputation
+        // fixed above) actually fires and isn't dead code; it does not
+        // claim the input is a cryptographically real attack block.
+        let initial_state = H;
+        let block = GenericArray::clone_from_slice(&[0x5au8; 64]);
+        let words = expand(&block);
+        let snapshots = round_snapshots(initial_state, &words, [K0, K1, K2, K3]);
+
+        let dv = DISTURBANCE_VECTORS[0];
+        let mut perturbed = words;
+        perturbed[dv.word] ^= 1 << dv.bit;
+        for t in (dv.word + 1).max(16)..80 {
+            perturbed[t] =
+                (perturbed[t - 3] ^ perturbed[t - 8] ^ perturbed[t - 14] ^ perturbed[t - 16])
+                    .rotate_left(1);
+        }
+        let final_regs = run_rounds(snapshots[dv.word], &perturbed, dv.word, [K0, K1, K2, K3]);
+        let candidate = [
+            initial_state[0].wrapping_add(final_regs[0]),
+            initial_state[1].wrapping_add(final_regs[1]),
+            initial_state[2].wrapping_add(final_regs[2]),
+            initial_state[3].wrapping_add(final_regs[3]),
+            initial_state[4].wrapping_add(final_regs[4]),
+        ];
+
+        // One bit away from the candidate: close enough to flag, but not
+        // so close that the `candidate != genuine` guard excludes it.
+        let mut genuine = candidate;
+        genuine[0] ^= 1;
+
+        assert!(detect_block(initial_state, &snapshots, &words, genuine));
+    }
+
+    #[test]
+    fn safe_hash_matches_genuine_digest_when_no_attack_detected() {
+        let cd = Sha1Cd::default().with_safe_hash();
+        let mut cd2 = cd.clone();
+        cd2.update(b"honest input");
+        let safe = cd2.clone().fixed_result();
+
+        let mut plain = Sha1Cd::default();
+        plain.update(b"honest input");
+        let genuine = plain.fixed_result();
+
+        assert_eq!(safe, genuine);
+    }
+}
@@ -0,0 +1,19 @@
+//! Constants used by the SHA-1 compression function.
+#![allow(dead_code)]
+
+/// Number of 32-bit words in the SHA-1 state.
+pub const STATE_LEN: usize = 5;
+/// Number of 32-bit words in a SHA-1 message block.
+pub const BLOCK_LEN: usize = 16;
+
+/// Initial hash value as defined by FIPS 180-4.
+pub const H: [u32; STATE_LEN] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+/// Round constant used in rounds 0..20.
+pub const K0: u32 = 0x5A827999u32;
+/// Round constant used in rounds 20..40.
+pub const K1: u32 = 0x6ED9EBA1u32;
+/// Round constant used in rounds 40..60.
+pub const K2: u32 = 0x8F1BBCDCu32;
+/// Round constant used in rounds 60..80.
+pub const K3: u32 = 0xCA62C1D6u32;
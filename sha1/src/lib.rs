@@ -68,9 +68,23 @@ extern crate std;
 #[cfg(feature = "asm-aarch64")]
 mod aarch64;
 mod consts;
-#[cfg(any(not(feature = "asm"), feature = "asm-aarch64"))]
+#[cfg(any(
+    not(feature = "asm"),
+    feature = "asm-aarch64",
+    target_arch = "x86",
+    target_arch = "x86_64"
+))]
 mod utils;
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+mod x86;
+
+pub mod cd;
+#[cfg(feature = "std")]
+pub mod many;
 
+pub use crate::cd::Sha1Cd;
+#[cfg(feature = "std")]
+pub use crate::many::{hash_many, Sha1x4};
 pub use digest::{self, Digest};
 
 use crate::consts::{H, STATE_LEN};
@@ -82,7 +96,10 @@ use digest::generic_array::GenericArray;
 use digest::impl_write;
 use digest::{BlockInput, FixedOutput, Reset, Update};
 
-#[cfg(not(feature = "asm"))]
+#[cfg(all(
+    not(any(target_arch = "x86", target_arch = "x86_64")),
+    not(feature = "asm")
+))]
 use crate::utils::compress;
 
 /// Structure representing the state of a SHA-1 computation
@@ -103,6 +120,56 @@ impl Default for Sha1 {
     }
 }
 
+impl Sha1 {
+    /// Constructs a `Sha1` from a previously exported midstate, resuming a
+    /// checkpointed stream or seeding a non-default initial hash value.
+    ///
+    /// `processed_len` is the number of bytes already absorbed into
+    /// `state`; finalization uses it to compute the big-endian bit-length
+    /// padding, so resuming with the wrong length produces an incorrect
+    /// digest. Pass `0` along with a custom `state` to hash with a custom
+    /// initial value instead of resuming a stream.
+    ///
+    /// `processed_len` must be a multiple of the 64-byte block size: the
+    /// fresh `buffer` this constructs starts empty, at internal position 0,
+    /// so a non-aligned `processed_len` would disagree with it about where
+    /// the current block boundary is and corrupt every block compressed
+    /// from then on. This is not a real restriction for the motivating
+    /// use cases: checkpointing always happens at a block boundary, and
+    /// resuming from a digest for a length-extension attack uses the
+    /// length *after* that message's own SHA-1 padding, which is always a
+    /// multiple of 64.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `processed_len % 64 != 0`.
+    pub fn from_midstate(state: [u32; STATE_LEN], processed_len: u64) -> Self {
+        assert_eq!(
+            processed_len % 64,
+            0,
+            "Sha1::from_midstate: processed_len must be a multiple of the 64-byte block size"
+        );
+        Sha1 {
+            h: state,
+            len: processed_len,
+            buffer: Default::default(),
+        }
+    }
+
+    /// Exports the current intermediate state: the chaining value and the
+    /// number of bytes absorbed so far. Pass both to
+    /// [`Sha1::from_midstate`] to resume hashing later, e.g. after
+    /// checkpointing a long-running stream to disk.
+    ///
+    /// Only meaningful at a block boundary (a multiple of 64 bytes
+    /// processed): bytes buffered since the last full block aren't part of
+    /// the exported state, and [`Sha1::from_midstate`] requires a
+    /// block-aligned length.
+    pub fn midstate(&self) -> ([u32; STATE_LEN], u64) {
+        (self.h, self.len)
+    }
+}
+
 impl BlockInput for Sha1 {
     type BlockSize = U64;
 }
@@ -110,10 +177,27 @@ impl BlockInput for Sha1 {
 impl Update for Sha1 {
     fn update(&mut self, input: impl AsRef<[u8]>) {
         let input = input.as_ref();
+        // The number of bytes absorbed before this call is always a
+        // multiple of 64 plus whatever's currently sitting in `self.buffer`;
+        // `self.len % 64` before we add `input.len()` below is exactly that
+        // buffered amount.
+        let buffered = (self.len % 64) as usize;
         // Assumes that `length_bits<<3` will not overflow
         self.len += input.len() as u64;
         let state = &mut self.h;
-        self.buffer.input(input, |d| compress(state, d));
+
+        if buffered == 0 {
+            let full_len = input.len() - input.len() % 64;
+            let (blocks, rest) = input.split_at(full_len);
+            if !blocks.is_empty() {
+                compress(state, as_blocks(blocks));
+            }
+            self.buffer
+                .input(rest, |d| compress(state, core::slice::from_ref(d)));
+        } else {
+            self.buffer
+                .input(input, |d| compress(state, core::slice::from_ref(d)));
+        }
     }
 }
 
@@ -125,7 +209,7 @@ impl FixedOutput for Sha1 {
             let state = &mut self.h;
             let l = self.len << 3;
             self.buffer
-                .len64_padding::<BE, _>(l, |d| compress(state, d));
+                .len64_padding::<BE, _>(l, |d| compress(state, core::slice::from_ref(d)));
         }
         let mut out = GenericArray::default();
         BE::write_u32_into(&self.h, &mut out);
@@ -133,6 +217,25 @@ impl FixedOutput for Sha1 {
     }
 }
 
+/// Reinterprets a byte slice as a slice of 64-byte blocks.
+///
+/// `GenericArray<u8, U64>` has the same layout as `[u8; 64]`, so this is
+/// just a free reinterpretation of already block-aligned bytes — the same
+/// trick the `asm`/SHA-NI backends already use per block, applied across
+/// the whole slice at once. `bytes.len()` must be a multiple of 64; callers
+/// only ever pass the block-aligned prefix of an input, so that always
+/// holds.
+#[allow(unsafe_code)]
+fn as_blocks(bytes: &[u8]) -> &[GenericArray<u8, U64>] {
+    debug_assert_eq!(bytes.len() % 64, 0);
+    unsafe {
+        core::slice::from_raw_parts(
+            bytes.as_ptr() as *const GenericArray<u8, U64>,
+            bytes.len() / 64,
+        )
+    }
+}
+
 impl Reset for Sha1 {
     fn reset(&mut self) {
         self.h = H;
@@ -141,28 +244,117 @@ impl Reset for Sha1 {
     }
 }
 
-#[cfg(all(feature = "asm", not(feature = "asm-aarch64")))]
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[inline(always)]
+fn compress(state: &mut [u32; 5], blocks: &[GenericArray<u8, U64>]) {
+    // Runtime-detected: falls back to the portable implementation (or the
+    // `asm` backend, if enabled) on CPUs without the SHA extension, and to
+    // the hardware SHA-NI instructions when it's available, all without
+    // requiring a special `target-feature` at compile time.
+    x86::Implementation::detect().compress(state, blocks);
+}
+
+#[cfg(all(
+    feature = "asm",
+    not(feature = "asm-aarch64"),
+    not(any(target_arch = "x86", target_arch = "x86_64"))
+))]
 #[inline(always)]
-fn compress(state: &mut [u32; 5], block: &GenericArray<u8, U64>) {
-    #[allow(unsafe_code)]
-    let block: &[u8; 64] = unsafe { core::mem::transmute(block) };
-    sha1_asm::compress(state, block);
+fn compress(state: &mut [u32; 5], blocks: &[GenericArray<u8, U64>]) {
+    for block in blocks {
+        #[allow(unsafe_code)]
+        let block: &[u8; 64] = unsafe { core::mem::transmute(block) };
+        sha1_asm::compress(state, block);
+    }
 }
 
 #[cfg(feature = "asm-aarch64")]
 #[inline(always)]
-fn compress(state: &mut [u32; 5], block: &GenericArray<u8, U64>) {
+fn compress(state: &mut [u32; 5], blocks: &[GenericArray<u8, U64>]) {
     // TODO: Replace this platform-specific call with is_aarch64_feature_detected!("sha1") once
     // that macro is stabilised and https://github.com/rust-lang/rfcs/pull/2725 is implemented
-    // to let us use it on no_std.
+    // to let us use it on no_std. Checked once per call rather than once per
+    // block, so a buffer full of blocks only pays for this dispatch once.
     if aarch64::sha1_supported() {
-        #[allow(unsafe_code)]
-        let block: &[u8; 64] = unsafe { core::mem::transmute(block) };
-        sha1_asm::compress(state, block);
+        for block in blocks {
+            #[allow(unsafe_code)]
+            let block: &[u8; 64] = unsafe { core::mem::transmute(block) };
+            sha1_asm::compress(state, block);
+        }
     } else {
-        utils::compress(state, block);
+        utils::compress(state, blocks);
     }
 }
 
 impl_opaque_debug!(Sha1);
 impl_write!(Sha1);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(data: &[u8]) -> [u8; 20] {
+        let mut hasher = Sha1::new();
+        hasher.update(data);
+        let mut out = [0u8; 20];
+        out.copy_from_slice(&hasher.result());
+        out
+    }
+
+    #[test]
+    fn midstate_round_trip_at_block_boundary() {
+        let mut first = [0u8; 64];
+        for (i, b) in first.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let rest = b"the rest of the message, spanning another block or so";
+
+        let mut whole = first.to_vec();
+        whole.extend_from_slice(rest);
+        let expected = digest(&whole);
+
+        let mut hasher = Sha1::new();
+        hasher.update(&first[..]);
+        let (state, len) = hasher.midstate();
+        assert_eq!(len, 64);
+
+        let mut resumed = Sha1::from_midstate(state, len);
+        resumed.update(rest);
+        let mut out = [0u8; 20];
+        out.copy_from_slice(&resumed.result());
+
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "multiple of the 64-byte block size")]
+    fn from_midstate_rejects_unaligned_len() {
+        Sha1::from_midstate(H, 63);
+    }
+
+    #[test]
+    fn batched_update_matches_byte_by_byte_update() {
+        // Several full blocks plus a remainder in a single `update` call,
+        // exercising the `compress(state, as_blocks(blocks))` batch path
+        // rather than the one-block-at-a-time `BlockBuffer` callback.
+        let mut data = [0u8; 200];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = (i * 7) as u8;
+        }
+
+        let mut one_shot = Sha1::new();
+        one_shot.update(&data[..]);
+
+        let mut byte_by_byte = Sha1::new();
+        for byte in &data {
+            byte_by_byte.update([*byte]);
+        }
+
+        let mut one_shot_out = [0u8; 20];
+        one_shot_out.copy_from_slice(&one_shot.result());
+        let mut byte_by_byte_out = [0u8; 20];
+        byte_by_byte_out.copy_from_slice(&byte_by_byte.result());
+
+        assert_eq!(one_shot_out, byte_by_byte_out);
+    }
+}
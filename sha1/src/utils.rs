@@ -0,0 +1,64 @@
+//! Portable, architecture-independent implementation of the SHA-1
+//! compression function. Used directly when no accelerated backend is
+//! available, and as the fallback path for runtime-detected backends.
+
+use crate::consts::{K0, K1, K2, K3, STATE_LEN};
+use digest::generic_array::typenum::U64;
+use digest::generic_array::GenericArray;
+
+#[inline(always)]
+fn to_schedule(block: &GenericArray<u8, U64>) -> [u32; 80] {
+    let mut w = [0u32; 80];
+    for (chunk, out) in block.chunks_exact(4).zip(w.iter_mut()) {
+        *out = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+    w
+}
+
+/// Processes each 64-byte block in `blocks` in turn, updating `state` in
+/// place. Looping here, rather than once per call, amortizes the call
+/// overhead across every buffered block in an `update`.
+pub fn compress(state: &mut [u32; STATE_LEN], blocks: &[GenericArray<u8, U64>]) {
+    for block in blocks {
+        compress_block(state, block);
+    }
+}
+
+fn compress_block(state: &mut [u32; STATE_LEN], block: &GenericArray<u8, U64>) {
+    let w = to_schedule(block);
+
+    let mut a = state[0];
+    let mut b = state[1];
+    let mut c = state[2];
+    let mut d = state[3];
+    let mut e = state[4];
+
+    for (i, &w_i) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | ((!b) & d), K0),
+            20..=39 => (b ^ c ^ d, K1),
+            40..=59 => ((b & c) | (b & d) | (c & d), K2),
+            _ => (b ^ c ^ d, K3),
+        };
+        let temp = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(w_i);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = temp;
+    }
+
+    state[0] = state[0].wrapping_add(a);
+    state[1] = state[1].wrapping_add(b);
+    state[2] = state[2].wrapping_add(c);
+    state[3] = state[3].wrapping_add(d);
+    state[4] = state[4].wrapping_add(e);
+}
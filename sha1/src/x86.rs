@@ -0,0 +1,326 @@
+//! Runtime detection and dispatch of the x86/x86_64 `SHA` CPU extension.
+//!
+//! Mirrors the runtime-detection pattern used for AArch64 in [`crate::aarch64`]:
+//! a single `cpuid` check is performed on first use and the chosen
+//! [`Implementation`] is cached, so a binary built for a generic target still
+//! benefits from hardware-accelerated SHA-1 when the running CPU supports it,
+//! without requiring `RUSTFLAGS="-C target-feature=+sha"` at compile time.
+
+#![allow(unsafe_code)]
+
+#[cfg(target_arch = "x86")]
+use core::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::*;
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::consts::STATE_LEN;
+use crate::utils::compress as compress_portable;
+use digest::generic_array::typenum::U64;
+use digest::generic_array::GenericArray;
+
+const UNINIT: u8 = 0;
+const PORTABLE: u8 = 1;
+const ASM: u8 = 2;
+const SHA: u8 = 3;
+
+static DETECTED: AtomicU8 = AtomicU8::new(UNINIT);
+
+/// The compression backend used to process message blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Implementation {
+    /// Portable, architecture-independent implementation from [`crate::utils`].
+    Portable,
+    /// Hand-written assembly routine from the `sha1-asm` crate.
+    Asm,
+    /// Hardware-accelerated implementation built on the x86 `SHA` extension.
+    Sha,
+}
+
+impl Implementation {
+    /// Detects the best implementation available on the current CPU. The
+    /// result of the `cpuid` check is cached, so repeated calls are a single
+    /// atomic load.
+    #[inline]
+    pub fn detect() -> Self {
+        match DETECTED.load(Ordering::Relaxed) {
+            PORTABLE => return Implementation::Portable,
+            ASM => return Implementation::Asm,
+            SHA => return Implementation::Sha,
+            _ => {}
+        }
+
+        let implementation = if sha_ni_supported() {
+            Implementation::Sha
+        } else if cfg!(feature = "asm") {
+            Implementation::Asm
+        } else {
+            Implementation::Portable
+        };
+
+        DETECTED.store(implementation.tag(), Ordering::Relaxed);
+        implementation
+    }
+
+    fn tag(self) -> u8 {
+        match self {
+            Implementation::Portable => PORTABLE,
+            Implementation::Asm => ASM,
+            Implementation::Sha => SHA,
+        }
+    }
+
+    /// Processes every block in `blocks` with the selected implementation.
+    /// Looping inside a single dispatch, rather than dispatching once per
+    /// block, matters most here: the `cpuid`-backed check this enum exists
+    /// to avoid paying per block is cheap, but the `asm`/SHA-NI call setup
+    /// is not free, and this amortizes it across the whole buffer.
+    #[inline]
+    pub fn compress(self, state: &mut [u32; STATE_LEN], blocks: &[GenericArray<u8, U64>]) {
+        match self {
+            Implementation::Portable => compress_portable(state, blocks),
+            #[cfg(feature = "asm")]
+            Implementation::Asm => {
+                for block in blocks {
+                    let block: &[u8; 64] = unsafe { core::mem::transmute(block) };
+                    sha1_asm::compress(state, block);
+                }
+            }
+            #[cfg(not(feature = "asm"))]
+            Implementation::Asm => compress_portable(state, blocks),
+            Implementation::Sha => unsafe { compress_sha_ni(state, blocks) },
+        }
+    }
+}
+
+/// Checks for the `SHA` CPU extension (and the SSE variants the SHA-NI
+/// intrinsics are built on) via `cpuid`, since `is_x86_feature_detected!` is
+/// not available in `#![no_std]` crates.
+fn sha_ni_supported() -> bool {
+    // `cpuid` itself requires at least a 486; bail out rather than trusting
+    // leaf 7 to exist if the CPU doesn't support querying it.
+    let max_leaf = unsafe { __cpuid(0) }.eax;
+    if max_leaf < 7 {
+        return false;
+    }
+
+    let leaf1_ecx = unsafe { __cpuid(1) }.ecx;
+    let ssse3 = leaf1_ecx & (1 << 9) != 0;
+    let sse4_1 = leaf1_ecx & (1 << 19) != 0;
+
+    let leaf7_ebx = unsafe { __cpuid_count(7, 0) }.ebx;
+    let sha = leaf7_ebx & (1 << 29) != 0;
+
+    sha && ssse3 && sse4_1
+}
+
+/// SHA-NI implementation of the SHA-1 block function, following Intel's
+/// published SHA extensions example code. Loads the state once and stores
+/// it once after processing every block in `blocks`, rather than per block,
+/// the same way the reference `while (length >= 64)` loop does.
+#[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+unsafe fn compress_sha_ni(state: &mut [u32; STATE_LEN], blocks: &[GenericArray<u8, U64>]) {
+    let mask = _mm_set_epi64x(0x0001_0203_0405_0607, 0x0809_0a0b_0c0d_0e0f);
+
+    let mut abcd = _mm_loadu_si128(state.as_ptr() as *const __m128i);
+    let mut e0 = _mm_set_epi32(state[4] as i32, 0, 0, 0);
+    abcd = _mm_shuffle_epi32(abcd, 0x1B);
+
+    for block in blocks {
+        let abcd_save = abcd;
+        let e0_save = e0;
+
+        let data = block.as_ptr() as *const __m128i;
+        let mut msg0 = _mm_shuffle_epi8(_mm_loadu_si128(data), mask);
+        let mut msg1 = _mm_shuffle_epi8(_mm_loadu_si128(data.add(1)), mask);
+        let mut msg2 = _mm_shuffle_epi8(_mm_loadu_si128(data.add(2)), mask);
+        let mut msg3 = _mm_shuffle_epi8(_mm_loadu_si128(data.add(3)), mask);
+        let mut e1;
+
+        // Rounds 0-3
+        e0 = _mm_add_epi32(e0, msg0);
+        e1 = abcd;
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 0);
+
+        // Rounds 4-7
+        e1 = _mm_sha1nexte_epu32(e1, msg1);
+        e0 = abcd;
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 0);
+        msg0 = _mm_sha1msg1_epu32(msg0, msg1);
+
+        // Rounds 8-11
+        e0 = _mm_sha1nexte_epu32(e0, msg2);
+        e1 = abcd;
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 0);
+        msg1 = _mm_sha1msg1_epu32(msg1, msg2);
+        msg0 = _mm_xor_si128(msg0, msg2);
+
+        // Rounds 12-15
+        e1 = _mm_sha1nexte_epu32(e1, msg3);
+        e0 = abcd;
+        msg0 = _mm_sha1msg2_epu32(msg0, msg3);
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 0);
+        msg2 = _mm_sha1msg1_epu32(msg2, msg3);
+        msg1 = _mm_xor_si128(msg1, msg3);
+
+        // Rounds 16-19
+        e0 = _mm_sha1nexte_epu32(e0, msg0);
+        e1 = abcd;
+        msg1 = _mm_sha1msg2_epu32(msg1, msg0);
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 0);
+        msg3 = _mm_sha1msg1_epu32(msg3, msg0);
+        msg2 = _mm_xor_si128(msg2, msg0);
+
+        // Rounds 20-23
+        e1 = _mm_sha1nexte_epu32(e1, msg1);
+        e0 = abcd;
+        msg2 = _mm_sha1msg2_epu32(msg2, msg1);
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 1);
+        msg0 = _mm_sha1msg1_epu32(msg0, msg1);
+        msg3 = _mm_xor_si128(msg3, msg1);
+
+        // Rounds 24-27
+        e0 = _mm_sha1nexte_epu32(e0, msg2);
+        e1 = abcd;
+        msg3 = _mm_sha1msg2_epu32(msg3, msg2);
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 1);
+        msg1 = _mm_sha1msg1_epu32(msg1, msg2);
+        msg0 = _mm_xor_si128(msg0, msg2);
+
+        // Rounds 28-31
+        e1 = _mm_sha1nexte_epu32(e1, msg3);
+        e0 = abcd;
+        msg0 = _mm_sha1msg2_epu32(msg0, msg3);
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 1);
+        msg2 = _mm_sha1msg1_epu32(msg2, msg3);
+        msg1 = _mm_xor_si128(msg1, msg3);
+
+        // Rounds 32-35
+        e0 = _mm_sha1nexte_epu32(e0, msg0);
+        e1 = abcd;
+        msg1 = _mm_sha1msg2_epu32(msg1, msg0);
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 1);
+        msg3 = _mm_sha1msg1_epu32(msg3, msg0);
+        msg2 = _mm_xor_si128(msg2, msg0);
+
+        // Rounds 36-39
+        e1 = _mm_sha1nexte_epu32(e1, msg1);
+        e0 = abcd;
+        msg2 = _mm_sha1msg2_epu32(msg2, msg1);
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 1);
+        msg0 = _mm_sha1msg1_epu32(msg0, msg1);
+        msg3 = _mm_xor_si128(msg3, msg1);
+
+        // Rounds 40-43
+        e0 = _mm_sha1nexte_epu32(e0, msg2);
+        e1 = abcd;
+        msg3 = _mm_sha1msg2_epu32(msg3, msg2);
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 2);
+        msg1 = _mm_sha1msg1_epu32(msg1, msg2);
+        msg0 = _mm_xor_si128(msg0, msg2);
+
+        // Rounds 44-47
+        e1 = _mm_sha1nexte_epu32(e1, msg3);
+        e0 = abcd;
+        msg0 = _mm_sha1msg2_epu32(msg0, msg3);
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 2);
+        msg2 = _mm_sha1msg1_epu32(msg2, msg3);
+        msg1 = _mm_xor_si128(msg1, msg3);
+
+        // Rounds 48-51
+        e0 = _mm_sha1nexte_epu32(e0, msg0);
+        e1 = abcd;
+        msg1 = _mm_sha1msg2_epu32(msg1, msg0);
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 2);
+        msg3 = _mm_sha1msg1_epu32(msg3, msg0);
+        msg2 = _mm_xor_si128(msg2, msg0);
+
+        // Rounds 52-55
+        e1 = _mm_sha1nexte_epu32(e1, msg1);
+        e0 = abcd;
+        msg2 = _mm_sha1msg2_epu32(msg2, msg1);
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 2);
+        msg0 = _mm_sha1msg1_epu32(msg0, msg1);
+        msg3 = _mm_xor_si128(msg3, msg1);
+
+        // Rounds 56-59
+        e0 = _mm_sha1nexte_epu32(e0, msg2);
+        e1 = abcd;
+        msg3 = _mm_sha1msg2_epu32(msg3, msg2);
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 2);
+        msg1 = _mm_sha1msg1_epu32(msg1, msg2);
+        msg0 = _mm_xor_si128(msg0, msg2);
+
+        // Rounds 60-63
+        e1 = _mm_sha1nexte_epu32(e1, msg3);
+        e0 = abcd;
+        msg0 = _mm_sha1msg2_epu32(msg0, msg3);
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 3);
+        msg2 = _mm_sha1msg1_epu32(msg2, msg3);
+        msg1 = _mm_xor_si128(msg1, msg3);
+
+        // Rounds 64-67
+        e0 = _mm_sha1nexte_epu32(e0, msg0);
+        e1 = abcd;
+        msg1 = _mm_sha1msg2_epu32(msg1, msg0);
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 3);
+        msg3 = _mm_sha1msg1_epu32(msg3, msg0);
+        msg2 = _mm_xor_si128(msg2, msg0);
+
+        // Rounds 68-71
+        e1 = _mm_sha1nexte_epu32(e1, msg1);
+        e0 = abcd;
+        msg2 = _mm_sha1msg2_epu32(msg2, msg1);
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 3);
+        msg3 = _mm_xor_si128(msg3, msg1);
+
+        // Rounds 72-75
+        e0 = _mm_sha1nexte_epu32(e0, msg2);
+        e1 = abcd;
+        msg3 = _mm_sha1msg2_epu32(msg3, msg2);
+        abcd = _mm_sha1rnds4_epu32(abcd, e0, 3);
+
+        // Rounds 76-79
+        e1 = _mm_sha1nexte_epu32(e1, msg3);
+        e0 = abcd;
+        abcd = _mm_sha1rnds4_epu32(abcd, e1, 3);
+
+        abcd = _mm_add_epi32(abcd, abcd_save);
+        e0 = _mm_sha1nexte_epu32(e0, e0_save);
+    }
+
+    abcd = _mm_shuffle_epi32(abcd, 0x1B);
+    _mm_storeu_si128(state.as_mut_ptr() as *mut __m128i, abcd);
+    state[4] = _mm_extract_epi32(e0, 3) as u32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consts::H;
+
+    fn block(fill: u8) -> GenericArray<u8, U64> {
+        GenericArray::clone_from_slice(&[fill; 64])
+    }
+
+    #[test]
+    fn batch_compress_matches_one_block_at_a_time() {
+        // The whole point of accepting a slice of blocks is to amortize
+        // dispatch across them instead of compressing one at a time; make
+        // sure that's actually equivalent to the one-at-a-time loop it
+        // replaced, using the portable backend so this runs regardless of
+        // which CPU features happen to be available.
+        let blocks = [block(0x11), block(0x22), block(0x33)];
+
+        let mut batched = H;
+        Implementation::Portable.compress(&mut batched, &blocks);
+
+        let mut sequential = H;
+        for b in &blocks {
+            Implementation::Portable.compress(&mut sequential, core::slice::from_ref(b));
+        }
+
+        assert_eq!(batched, sequential);
+    }
+}